@@ -2,24 +2,40 @@
 
 extern crate alloc;
 
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::{ffi::c_void, mem, ptr};
 
 use nsis_plugin_api::*;
 use windows_sys::Win32::{
-    Foundation::{CloseHandle, HANDLE},
+    Foundation::{
+        CloseHandle, GetLastError, BOOL, ERROR_INVALID_PARAMETER, HANDLE, HWND, LPARAM,
+        WAIT_OBJECT_0,
+    },
     Security::{EqualSid, GetTokenInformation, TokenUser, TOKEN_QUERY, TOKEN_USER},
     System::{
         Diagnostics::ToolHelp::{
-            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
-            TH32CS_SNAPPROCESS,
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, Thread32First,
+            Thread32Next, PROCESSENTRY32W, THREADENTRY32, TH32CS_SNAPPROCESS, TH32CS_SNAPTHREAD,
         },
         Threading::{
-            GetCurrentProcessId, OpenProcess, OpenProcessToken, TerminateProcess,
-            PROCESS_QUERY_INFORMATION, PROCESS_TERMINATE,
+            CreateProcessW, DeleteProcThreadAttributeList, GetCurrentProcessId,
+            InitializeProcThreadAttributeList, OpenProcess, OpenProcessToken,
+            QueryFullProcessImageNameW, TerminateProcess, UpdateProcThreadAttribute,
+            WaitForSingleObject, CREATE_NEW_PROCESS_GROUP,
+            CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT,
+            LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_CREATE_PROCESS, PROCESS_INFORMATION,
+            PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE,
+            PROCESS_TERMINATE,
+            PROC_THREAD_ATTRIBUTE_PARENT_PROCESS, STARTUPINFOEXW, STARTUPINFOW,
         },
     },
+    UI::WindowsAndMessaging::{
+        EnumWindows, GetShellWindow, GetWindowThreadProcessId, PostMessageW, PostThreadMessageW,
+        WM_CLOSE, WM_QUIT,
+    },
 };
 
 nsis_plugin!();
@@ -118,6 +134,246 @@ fn KillProcessCurrentUser() -> Result<(), Error> {
     }
 }
 
+/// Kill all running process with the given name, skipping processes with the host's pid, after first asking them to close cleanly and waiting up to the given timeout before force-terminating. The input and process names are case-insensitive.
+///
+/// # Safety
+///
+/// This function always expects 2 values on the stack ($1: name, $2: timeout in milliseconds) and will panic otherwise.
+#[nsis_fn]
+fn KillProcessGracefully() -> Result<(), Error> {
+    let name = popstr()?;
+    let timeout = popstr()?.parse::<u32>().unwrap_or(5000);
+
+    let processes = get_processes(&name);
+
+    if !processes.is_empty()
+        && processes
+            .into_iter()
+            .map(|pid| kill_gracefully(pid, timeout))
+            .all(|b| b)
+    {
+        push(ZERO)
+    } else {
+        push(ONE)
+    }
+}
+
+/// Kill all running process with the given name that belong to the current user, skipping processes with the host's pid, after first asking them to close cleanly and waiting up to the given timeout before force-terminating. The input and process names are case-insensitive.
+///
+/// # Safety
+///
+/// This function always expects 2 values on the stack ($1: name, $2: timeout in milliseconds) and will panic otherwise.
+#[nsis_fn]
+fn KillProcessCurrentUserGracefully() -> Result<(), Error> {
+    let name = popstr()?;
+    let timeout = popstr()?.parse::<u32>().unwrap_or(5000);
+
+    let processes = get_processes(&name);
+
+    if processes.is_empty() {
+        return push(ONE);
+    }
+
+    let success = if let Some(user_sid) = get_sid(GetCurrentProcessId()) {
+        processes
+            .into_iter()
+            .filter(|pid| belongs_to_user(user_sid, *pid))
+            .map(|pid| kill_gracefully(pid, timeout))
+            .all(|b| b)
+    } else {
+        processes
+            .into_iter()
+            .map(|pid| kill_gracefully(pid, timeout))
+            .all(|b| b)
+    };
+
+    if success {
+        push(ZERO)
+    } else {
+        push(ONE)
+    }
+}
+
+/// Push the pids of every running process with the given name, skipping processes with the host's pid. Each pid is pushed as a decimal string and the count is pushed last (on top of the stack), so a script can pop the count and then that many pids. The input and process names are case-insensitive.
+///
+/// # Safety
+///
+/// This function always expects 1 string on the stack ($1: name) and will panic otherwise.
+#[nsis_fn]
+fn GetProcessPids() -> Result<(), Error> {
+    let name = popstr()?;
+
+    let processes = get_processes(&name);
+
+    for entry in pids_stack(&processes) {
+        push(&entry)?;
+    }
+
+    Ok(())
+}
+
+// Lay out the pids for the NSIS stack: each pid as a decimal string followed by the count, so the
+// count ends up on top and a script can pop it and then that many pids.
+fn pids_stack(processes: &[u32]) -> Vec<String> {
+    let mut stack: Vec<String> = processes.iter().rev().map(|pid| pid.to_string()).collect();
+    stack.push(processes.len().to_string());
+    stack
+}
+
+/// Test if there is a running process whose full executable path matches the given path, skipping processes with the host's pid. The input and image paths are compared case-insensitively.
+///
+/// The comparison is a plain case-insensitive string match against the path reported by
+/// `QueryFullProcessImageNameW`; it does not canonicalize either side, so equivalent-but-differing
+/// forms (8.3 short paths, forward vs back slashes, `\\?\` prefixes) will not match. Pass the same
+/// normalized, long, back-slashed path the OS reports (e.g. `$INSTDIR\app.exe`).
+///
+/// # Safety
+///
+/// This function always expects 1 string on the stack ($1: path) and will panic otherwise.
+#[nsis_fn]
+fn FindProcessByPath() -> Result<(), Error> {
+    let path = popstr()?;
+
+    if !get_processes_by_path(&path).is_empty() {
+        push(ZERO)
+    } else {
+        push(ONE)
+    }
+}
+
+/// Kill all running process whose full executable path matches the given path, skipping processes with the host's pid. The input and image paths are compared case-insensitively.
+///
+/// See [`FindProcessByPath`] for the caveat that the path is matched verbatim (no canonicalization).
+///
+/// # Safety
+///
+/// This function always expects 1 string on the stack ($1: path) and will panic otherwise.
+#[nsis_fn]
+fn KillProcessByPath() -> Result<(), Error> {
+    let path = popstr()?;
+
+    let processes = get_processes_by_path(&path);
+
+    if !processes.is_empty() && processes.into_iter().map(kill).all(|b| b) {
+        push(ZERO)
+    } else {
+        push(ONE)
+    }
+}
+
+/// Kill the entire process tree (the matching processes and all of their transitive children) for the given name, skipping processes with the host's pid. Children are terminated before their parents. The input and process names are case-insensitive.
+///
+/// # Safety
+///
+/// This function always expects 1 string on the stack ($1: name) and will panic otherwise.
+#[nsis_fn]
+fn KillProcessTree() -> Result<(), Error> {
+    let name = popstr()?;
+
+    let processes = get_process_tree(&name);
+
+    if !processes.is_empty() && processes.into_iter().map(kill).all(|b| b) {
+        push(ZERO)
+    } else {
+        push(ONE)
+    }
+}
+
+/// Launch a command unelevated from an elevated installer by re-parenting it to the desktop shell, so the new process inherits the shell's medium-integrity token.
+///
+/// # Safety
+///
+/// This function always expects 1 string on the stack ($1: command line) and will panic otherwise.
+#[nsis_fn]
+fn RunAsUser() -> Result<(), Error> {
+    let command = popstr()?;
+
+    if run_as_user(&command) {
+        push(ZERO)
+    } else {
+        push(ONE)
+    }
+}
+
+// Spawn `command` with the desktop shell as its parent process so it runs with the
+// shell's (unelevated) token. Returns false on any failure.
+unsafe fn run_as_user(command: &str) -> bool {
+    let shell = GetShellWindow();
+    if shell.is_null() {
+        return false;
+    }
+
+    let mut shell_pid = 0u32;
+    GetWindowThreadProcessId(shell, &mut shell_pid);
+    if shell_pid == 0 {
+        return false;
+    }
+
+    let shell_process = OpenProcess(PROCESS_CREATE_PROCESS, 0, shell_pid);
+    if shell_process.is_null() {
+        return false;
+    }
+
+    // Size the attribute list, allocate the backing buffer, then initialize it in place.
+    let mut size = 0;
+    InitializeProcThreadAttributeList(ptr::null_mut(), 1, 0, &mut size);
+    let mut attribute_list = vec![0u8; size];
+    let attribute_list = attribute_list.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+
+    let mut success = InitializeProcThreadAttributeList(attribute_list, 1, 0, &mut size) != 0
+        && UpdateProcThreadAttribute(
+            attribute_list,
+            0,
+            PROC_THREAD_ATTRIBUTE_PARENT_PROCESS as usize,
+            &shell_process as *const HANDLE as *const c_void,
+            mem::size_of::<HANDLE>(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        ) != 0;
+
+    if success {
+        let mut startup_info: STARTUPINFOEXW = mem::zeroed();
+        startup_info.StartupInfo.cb = mem::size_of::<STARTUPINFOEXW>() as u32;
+        startup_info.lpAttributeList = attribute_list;
+
+        let mut process_info: PROCESS_INFORMATION = mem::zeroed();
+
+        // CreateProcessW may mutate the command line buffer, so it must be owned and writable.
+        let mut command = encode_utf16(command);
+
+        success = CreateProcessW(
+            ptr::null(),
+            command.as_mut_ptr(),
+            ptr::null(),
+            ptr::null(),
+            0,
+            EXTENDED_STARTUPINFO_PRESENT
+                | CREATE_NEW_PROCESS_GROUP
+                | CREATE_UNICODE_ENVIRONMENT,
+            ptr::null(),
+            ptr::null(),
+            &startup_info as *const STARTUPINFOEXW as *const STARTUPINFOW,
+            &mut process_info,
+        ) != 0;
+
+        if success {
+            CloseHandle(process_info.hProcess);
+            CloseHandle(process_info.hThread);
+        }
+
+        DeleteProcThreadAttributeList(attribute_list);
+    }
+
+    CloseHandle(shell_process);
+
+    success
+}
+
+// Encode a string as a nul-terminated UTF-16 buffer for the wide Win32 APIs.
+fn encode_utf16(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(core::iter::once(0)).collect()
+}
+
 unsafe fn belongs_to_user(user_sid: *mut c_void, pid: u32) -> bool {
     let p_sid = get_sid(pid);
     // Trying to get the sid of a process of another user will give us an "Access Denied" error.
@@ -136,6 +392,79 @@ fn kill(pid: u32) -> bool {
     }
 }
 
+// Ask a process to close cleanly, then wait up to `timeout` milliseconds for it to exit,
+// falling back to TerminateProcess if it doesn't. Returns whether the process ended up gone.
+fn kill_gracefully(pid: u32, timeout: u32) -> bool {
+    unsafe {
+        request_close(pid);
+
+        let handle = OpenProcess(PROCESS_SYNCHRONIZE | PROCESS_TERMINATE, 0, pid);
+        if handle.is_null() {
+            // The process may already be gone — the clean close can win the race before we open
+            // it. Windows reports an invalid pid as ERROR_INVALID_PARAMETER, so treat that as the
+            // success we were after instead of forcing a doomed TerminateProcess on a null handle.
+            if GetLastError() == ERROR_INVALID_PARAMETER {
+                return true;
+            }
+            // Otherwise we genuinely couldn't open it; fall back to the forceful path.
+            return kill(pid);
+        }
+
+        let success = if WaitForSingleObject(handle, timeout) == WAIT_OBJECT_0 {
+            true
+        } else {
+            TerminateProcess(handle, 1) != 0
+        };
+
+        CloseHandle(handle);
+        success
+    }
+}
+
+// Politely ask every window and thread owned by `pid` to close.
+unsafe fn request_close(pid: u32) {
+    EnumWindows(Some(enum_close_windows), pid as LPARAM);
+
+    // Console and message-only processes have no top-level window, so also post WM_QUIT to each
+    // of their threads as a best-effort clean shutdown. (GenerateConsoleCtrlEvent is intentionally
+    // not used here: its second argument is a process-group id, not a pid, and the event only
+    // reaches processes sharing our console, so passing an arbitrary app pid is a no-op.)
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+
+    let mut thread = THREADENTRY32 {
+        dwSize: mem::size_of::<THREADENTRY32>() as u32,
+        ..mem::zeroed()
+    };
+
+    if Thread32First(snapshot, &mut thread) != 0 {
+        loop {
+            if thread.th32OwnerProcessID == pid {
+                PostThreadMessageW(thread.th32ThreadID, WM_QUIT, 0, 0);
+            }
+            if Thread32Next(snapshot, &mut thread) == 0 {
+                break;
+            }
+        }
+    }
+
+    CloseHandle(snapshot);
+}
+
+// EnumWindows callback: post WM_CLOSE to every top-level window owned by the target pid (passed in lparam).
+unsafe extern "system" fn enum_close_windows(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let target_pid = lparam as u32;
+
+    let mut pid = 0;
+    GetWindowThreadProcessId(hwnd, &mut pid);
+
+    if pid == target_pid {
+        PostMessageW(hwnd, WM_CLOSE, 0, 0);
+    }
+
+    // Keep enumerating.
+    1
+}
+
 // Get the SID of a process. Returns None on error.
 unsafe fn get_sid(pid: u32) -> Option<*mut c_void> {
     let handle = OpenProcess(PROCESS_QUERY_INFORMATION, 0, pid);
@@ -194,12 +523,16 @@ fn get_processes(name: &str) -> Vec<u32> {
         };
 
         if Process32FirstW(handle, &mut process) != 0 {
-            while Process32NextW(handle, &mut process) != 0 {
+            loop {
                 if current_pid != process.th32ProcessID
                     && decode_utf16_lossy(&process.szExeFile).to_lowercase() == name.to_lowercase()
                 {
                     processes.push(process.th32ProcessID);
                 }
+
+                if Process32NextW(handle, &mut process) == 0 {
+                    break;
+                }
             }
         }
 
@@ -209,6 +542,121 @@ fn get_processes(name: &str) -> Vec<u32> {
     processes
 }
 
+// Take a single snapshot of every process as (pid, parent pid, name).
+fn get_all_processes() -> Vec<(u32, u32, String)> {
+    let mut processes = Vec::new();
+
+    unsafe {
+        let handle = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+
+        let mut process = PROCESSENTRY32W {
+            dwSize: mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..mem::zeroed()
+        };
+
+        if Process32FirstW(handle, &mut process) != 0 {
+            loop {
+                processes.push((
+                    process.th32ProcessID,
+                    process.th32ParentProcessID,
+                    decode_utf16_lossy(&process.szExeFile),
+                ));
+
+                if Process32NextW(handle, &mut process) == 0 {
+                    break;
+                }
+            }
+        }
+
+        CloseHandle(handle);
+    }
+
+    processes
+}
+
+// Collect the pids of every process whose real image path matches `path` (case-insensitive),
+// skipping the host's pid. The match is verbatim against the path reported by
+// QueryFullProcessImageNameW — neither side is canonicalized (see FindProcessByPath docs).
+fn get_processes_by_path(path: &str) -> Vec<u32> {
+    let current_pid = unsafe { GetCurrentProcessId() };
+    let path = path.to_lowercase();
+
+    get_all_processes()
+        .into_iter()
+        .filter(|(pid, _, _)| *pid != current_pid)
+        .filter_map(|(pid, _, _)| {
+            let image = unsafe { get_image_path(pid) }?;
+            (image.to_lowercase() == path).then_some(pid)
+        })
+        .collect()
+}
+
+// Query the full executable path of a process. Returns None on error (e.g. access denied).
+unsafe fn get_image_path(pid: u32) -> Option<String> {
+    let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+    if handle.is_null() {
+        return None;
+    }
+
+    let mut buffer = vec![0u16; 1024];
+    let mut size = buffer.len() as u32;
+    let success = QueryFullProcessImageNameW(handle, 0, buffer.as_mut_ptr(), &mut size);
+
+    CloseHandle(handle);
+
+    if success == 0 {
+        return None;
+    }
+
+    Some(decode_utf16_lossy(&buffer[..size as usize]))
+}
+
+// Collect the pids of every process matching `name` and all of their transitive children,
+// ordered children-first so callers can terminate them before their parents. The host's pid
+// is never included, and a visited set guards against pid-reuse cycles.
+fn get_process_tree(name: &str) -> Vec<u32> {
+    let current_pid = unsafe { GetCurrentProcessId() };
+    let processes = get_all_processes();
+
+    let mut children: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for (pid, parent_pid, _) in &processes {
+        children.entry(*parent_pid).or_default().push(*pid);
+    }
+
+    let mut visited = BTreeSet::new();
+    let mut ordered = Vec::new();
+
+    for (pid, _, exe) in &processes {
+        if *pid != current_pid && exe.to_lowercase() == name.to_lowercase() {
+            collect_descendants(*pid, current_pid, &children, &mut visited, &mut ordered);
+        }
+    }
+
+    ordered
+}
+
+// Depth-first post-order walk: push each node after its children so the returned order kills
+// descendants before ancestors.
+fn collect_descendants(
+    pid: u32,
+    current_pid: u32,
+    children: &BTreeMap<u32, Vec<u32>>,
+    visited: &mut BTreeSet<u32>,
+    ordered: &mut Vec<u32>,
+) {
+    if pid == current_pid || !visited.insert(pid) {
+        return;
+    }
+
+    if let Some(kids) = children.get(&pid) {
+        for &child in kids {
+            collect_descendants(child, current_pid, children, visited, ordered);
+        }
+    }
+
+    ordered.push(pid);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,4 +674,67 @@ mod tests {
         // This will return true on empty iterators so it's basically no-op right now
         assert!(processes.into_iter().map(kill).all(|b| b));
     }
+
+    #[test]
+    fn encode_utf16_is_nul_terminated() {
+        assert_eq!(encode_utf16(""), [0]);
+        assert_eq!(encode_utf16("ab"), [b'a' as u16, b'b' as u16, 0]);
+    }
+
+    fn tree(edges: &[(u32, u32)]) -> BTreeMap<u32, Vec<u32>> {
+        let mut children: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for &(parent, child) in edges {
+            children.entry(parent).or_default().push(child);
+        }
+        children
+    }
+
+    #[test]
+    fn collect_descendants_is_children_before_parents() {
+        // 1 -> {2, 3}, 2 -> {4}
+        let children = tree(&[(1, 2), (1, 3), (2, 4)]);
+        let mut visited = BTreeSet::new();
+        let mut ordered = Vec::new();
+        collect_descendants(1, 0, &children, &mut visited, &mut ordered);
+
+        // Every child must come before its parent.
+        let pos = |pid: u32| ordered.iter().position(|&p| p == pid).unwrap();
+        assert!(pos(4) < pos(2));
+        assert!(pos(2) < pos(1));
+        assert!(pos(3) < pos(1));
+        assert_eq!(ordered.last(), Some(&1));
+    }
+
+    #[test]
+    fn collect_descendants_guards_against_cycles() {
+        // 1 -> 2 -> 1 (pid reuse cycle): must terminate without looping or duplicating.
+        let children = tree(&[(1, 2), (2, 1)]);
+        let mut visited = BTreeSet::new();
+        let mut ordered = Vec::new();
+        collect_descendants(1, 0, &children, &mut visited, &mut ordered);
+
+        let mut sorted = ordered.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(ordered.len(), sorted.len());
+        assert_eq!(sorted, [1, 2]);
+    }
+
+    #[test]
+    fn pids_stack_pushes_pids_then_count_on_top() {
+        assert_eq!(pids_stack(&[]), ["0"]);
+        // Pushed in order, so the count ("3") lands on top and the pids pop back in original order.
+        assert_eq!(pids_stack(&[10, 20, 30]), ["30", "20", "10", "3"]);
+    }
+
+    #[test]
+    fn collect_descendants_skips_host_pid() {
+        // The host pid (here 2) and its subtree must be left untouched.
+        let children = tree(&[(1, 2), (2, 4)]);
+        let mut visited = BTreeSet::new();
+        let mut ordered = Vec::new();
+        collect_descendants(1, 2, &children, &mut visited, &mut ordered);
+
+        assert_eq!(ordered, [1]);
+    }
 }